@@ -1,11 +1,11 @@
-use std::{str, collections::HashMap};
+use std::{str, borrow::Cow, collections::HashMap};
 use anyhow::{Result, bail};
 
 #[derive(Clone, Debug, PartialEq)]
-pub enum Token {
+pub enum Token<'src> {
     // user generated
-    Ident(String),
-    Lit(Literal),
+    Ident(&'src str),
+    Lit(Literal<'src>),
     // keywords
     Let,
     Mut,
@@ -58,54 +58,210 @@ pub enum Token {
     Tilde,
     Grave,
 
+    Comment { shape: CommentShape, doc: Option<DocPlacement>, text: &'src str },
+
+    /// A byte the lexer doesn't recognize. Carries the raw byte rather than
+    /// aborting, so a full pass can still tokenize the rest of the file.
+    Unknown(u8),
+
     EOF,
 }
 
+/// Whether a comment was written `//` or `/* */`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommentShape {
+    Line,
+    Block,
+}
+
+/// `///`/`/**` document the item that follows; `//!`/`/*!` document the
+/// item the comment is inside of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DocPlacement {
+    Outer,
+    Inner,
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Literal<'src> {
+    /// `invalid` is set when the literal was malformed (e.g. an unterminated
+    /// string); `value` is still the lexer's best-effort reading of it.
+    Str { value: Cow<'src, str>, invalid: bool },
+    Int { value: u64, radix: Radix, invalid: bool },
+    Float { value: f64, invalid: bool },
+}
+
+/// The base an integer literal was written in, from its `0x`/`0o`/`0b`
+/// prefix (or its absence, for plain decimal).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Radix {
+    Dec,
+    Hex,
+    Oct,
+    Bin,
+}
+
+impl Radix {
+    fn base(self) -> u32 {
+        match self {
+            Radix::Dec => 10,
+            Radix::Hex => 16,
+            Radix::Oct => 8,
+            Radix::Bin => 2,
+        }
+    }
+
+    fn is_digit(self, b: u8) -> bool {
+        match self {
+            Radix::Dec => b.is_ascii_digit(),
+            Radix::Hex => b.is_ascii_hexdigit(),
+            Radix::Oct => (b'0'..=b'7').contains(&b),
+            Radix::Bin => b == b'0' || b == b'1',
+        }
+    }
+}
 
+/// A non-fatal problem found while scanning a token, e.g. an unterminated
+/// string or an out-of-range escape. The lexer never panics or aborts on
+/// these; it records them here and keeps tokenizing.
 #[derive(Clone, Debug, PartialEq)]
-pub enum Literal {
-    Str(String),
-    Num(f64),
+pub struct LexError {
+    pub message: String,
+    pub span: Span,
+}
+
+/// A line/column location, used for diagnostics and tooling. `line` is
+/// 1-indexed so it can be printed directly; `column` is a 0-indexed byte
+/// offset from the start of that line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The byte range a token was scanned from, plus the `Position` of its first
+/// byte. The end offset is exclusive, so `&input[span.start..span.end]`
+/// recovers the token's source text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub start_pos: Position,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct LexicalAnalysis {
+struct LexicalAnalysis<'src> {
     lines: Vec<usize>, // we will store the char location of every newline here
-    tokens: HashMap<usize, Token> // each token is stored with the first char's location as its key
+    tokens: HashMap<usize, Token<'src>> // each token is stored with the first char's location as its key
+}
+
+impl<'src> LexicalAnalysis<'src> {
+    fn new() -> Self {
+        Self { lines: Vec::new(), tokens: HashMap::new() }
+    }
 }
 
-pub struct Lexer {
+pub struct Lexer<'src> {
     position: usize,
     read_position: usize,
     ch: u8,
-    input:Vec<u8>,
+    input: &'src str,
+    line: usize,
+    column: usize,
+    analysis: LexicalAnalysis<'src>,
+    errors: Vec<LexError>,
+    lookahead: Option<(Span, Token<'src>)>,
+    done: bool,
 }
 
-impl Lexer {
-    pub fn new(input: String) -> Self {
+impl<'src> Lexer<'src> {
+    pub fn new(input: &'src str) -> Self {
         let mut lex = Self {
             position: 0,
             read_position: 0,
             ch: 0,
-            input: input.into_bytes(),
+            input,
+            line: 1,
+            column: 0,
+            analysis: LexicalAnalysis::new(),
+            errors: Vec::new(),
+            lookahead: None,
+            done: false,
         };
         lex.next_char();
 
         lex
     }
 
+    /// Problems found while scanning so far. A malformed token is still
+    /// emitted (see [`Literal::Str`]'s `invalid` flag and [`Token::Unknown`]);
+    /// this is where the lexer records what was wrong with it.
+    pub fn errors(&self) -> &[LexError] {
+        &self.errors
+    }
+
+    /// Look at the next token without consuming it.
+    pub fn peek_token(&mut self) -> &(Span, Token<'src>) {
+        if self.lookahead.is_none() {
+            self.lookahead = Some(self.scan_token());
+        }
+        self.lookahead.as_ref().unwrap()
+    }
+
+    fn error(&mut self, span: Span, message: impl Into<String>) {
+        self.errors.push(LexError { message: message.into(), span });
+    }
+
+    fn bytes(&self) -> &'src [u8] {
+        self.input.as_bytes()
+    }
+
     fn next_char(&mut self) {
-        if self.read_position >= self.input.len() {
+        // `self.ch` is the byte we're about to move past; advance the
+        // position cursor based on it before it's overwritten.
+        if self.read_position > 0 {
+            if self.ch == b'\n' {
+                self.analysis.lines.push(self.position);
+                self.line += 1;
+                self.column = 0;
+            } else {
+                self.column += 1;
+            }
+        }
+
+        if self.read_position >= self.bytes().len() {
             self.ch = 0;
         } else {
-            self.ch = self.input[self.read_position]
+            self.ch = self.bytes()[self.read_position]
         }
 
         self.position = self.read_position;
         self.read_position += 1;
     }
 
-    pub fn next_token(&mut self) -> (usize, Token) {
+    fn current_position(&self) -> Position {
+        Position { line: self.line, column: self.column }
+    }
+
+    fn peek_byte(&self) -> Option<u8> {
+        self.bytes().get(self.read_position).copied()
+    }
+
+    /// Scan and consume the next token, returning a buffered lookahead from
+    /// [`Lexer::peek_token`] first if one is pending.
+    pub fn next_token(&mut self) -> (Span, Token<'src>) {
+        self.lookahead.take().unwrap_or_else(|| self.scan_token())
+    }
+
+    fn scan_token(&mut self) -> (Span, Token<'src>) {
+        let start = self.position;
+        let start_pos = self.current_position();
+        // `read_ident`/`read_number_literal` already advance `self.ch` past
+        // their own content to the first byte of the next token, unlike the
+        // single-char arms below; don't consume that byte a second time.
+        let mut advance = true;
+
         let tok = match self.ch {
             b'{'  => Token::LSquirly,
             b'}'  => Token::RSquirly,
@@ -133,16 +289,18 @@ impl Lexer {
             b'+'  => Token::Plus,
             b'|'  => Token::Pipe,
             b'\\' => Token::BSlash,
-            b'/'  => Token::FSlash,
+            b'/'  => self.read_slash(),
             b'~'  => Token::Tilde,
             b'`'  => Token::Grave,
             b'\t' => Token::Tab,
+            b'\n' => Token::NewLine,
             b' '  => self.read_whitespace(),
 
-            b'\'' | b'"' => Token::Lit(Literal::Str(self.read_string_literal().to_string())),
+            b'\'' | b'"' => Token::Lit(self.read_string_literal()),
             b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
                 let ident = self.read_ident();
-                match ident.as_str() {
+                advance = false;
+                match ident {
                     "let"    => Token::Let,
                     "mut"    => Token::Mut,
                     "def"    => Token::Def,
@@ -155,19 +313,25 @@ impl Lexer {
                     "match"  => Token::Match,
                     "true"   => Token::True,
                     "false"  => Token::False,
-                    _ => Token::Ident(ident.to_string())
+                    _ => Token::Ident(ident)
                 }
             },
-            b'0'..=b'9' => Token::Lit(Literal::Num(self.read_number_literal())),
+            b'0'..=b'9' => {
+                advance = false;
+                Token::Lit(self.read_number_literal())
+            }
             0 => Token::EOF,
-            _ => Token::EOF,
+            _ => Token::Unknown(self.ch),
         };
 
-        self.next_char();
-        (self.position, tok)
+        if advance {
+            self.next_char();
+        }
+        let span = Span { start, end: self.position, start_pos };
+        (span, tok)
     }
 
-    fn read_whitespace(&mut self) -> Token {
+    fn read_whitespace(&mut self) -> Token<'src> {
         if self.prev_match(b'\t') || self.prev_match(b'\n') {
             if let Ok(matched) = self.peek_match("   ") { // 4 spaces to a tab?
                 if matched {                              // there's got to be a better way...
@@ -178,64 +342,659 @@ impl Lexer {
                 }
                 else { Token:: Space }
             } else {
-                Token::EOF
+                // fewer than 3 bytes left to check the tab pattern against;
+                // it's still an ordinary space, not the end of input.
+                Token::Space
             }
         } else {
             Token::Space
         }
     }
 
-    fn read_number_literal(&mut self) -> f64 { 
-        let pos = self.position;
-        let mut decimal = false;
+    fn read_slash(&mut self) -> Token<'src> {
+        match self.peek_byte() {
+            Some(b'/') => self.read_line_comment(),
+            Some(b'*') => self.read_block_comment(),
+            _ => Token::FSlash,
+        }
+    }
+
+    fn read_line_comment(&mut self) -> Token<'src> {
+        let start = self.position;
+        self.next_char(); // consume first '/'
+        self.next_char(); // consume second '/'
+        // rustc doesn't treat `////...` as a doc comment (repeated slashes
+        // read as a plain separator, not a marker), only exactly `///`.
+        let doc = match self.ch {
+            b'/' if self.peek_byte() != Some(b'/') => Some(DocPlacement::Outer),
+            b'!' => Some(DocPlacement::Inner),
+            _ => None,
+        };
+        while self.ch != b'\n' && self.ch != 0 {
+            self.next_char();
+        }
+        Token::Comment {
+            shape: CommentShape::Line,
+            doc,
+            text: &self.input[start..self.position],
+        }
+    }
+
+    fn read_block_comment(&mut self) -> Token<'src> {
+        let start = self.position;
+        let start_pos = self.current_position();
+        self.next_char(); // consume '/'
+        self.next_char(); // consume '*'
+        // rustc doesn't treat `/***...` or the empty `/**/` as doc comments,
+        // only `/**` followed by real content.
+        let doc = match self.ch {
+            b'*' if !matches!(self.peek_byte(), Some(b'/' | b'*')) => Some(DocPlacement::Outer),
+            b'!' => Some(DocPlacement::Inner),
+            _ => None,
+        };
+
+        let mut depth = 1;
+        let end;
+        loop {
+            match self.ch {
+                0 => {
+                    self.error(Span { start, end: self.position, start_pos }, "unterminated block comment");
+                    end = self.position;
+                    break;
+                }
+                b'*' if self.peek_byte() == Some(b'/') => {
+                    self.next_char(); // consume '*', leaving self.ch on '/'
+                    depth -= 1;
+                    if depth == 0 {
+                        // closing '/' is still unconsumed (left for next_token's
+                        // trailing next_char()), but it's part of the comment text.
+                        end = self.position + 1;
+                        break;
+                    }
+                    self.next_char(); // consume '/', keep scanning the outer comment
+                }
+                b'/' if self.peek_byte() == Some(b'*') => {
+                    self.next_char(); // consume '/'
+                    self.next_char(); // consume '*'
+                    depth += 1;
+                }
+                _ => self.next_char(),
+            }
+        }
+
+        Token::Comment {
+            shape: CommentShape::Block,
+            doc,
+            text: &self.input[start..end],
+        }
+    }
+
+    fn read_number_literal(&mut self) -> Literal<'src> {
+        let start = self.position;
+        let start_pos = self.current_position();
+
+        let radix = if self.ch == b'0' {
+            match self.peek_byte() {
+                Some(b'x' | b'X') => Some(Radix::Hex),
+                Some(b'o' | b'O') => Some(Radix::Oct),
+                Some(b'b' | b'B') => Some(Radix::Bin),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        if let Some(radix) = radix {
+            self.next_char(); // consume '0'
+            self.next_char(); // consume x/o/b
+            return self.read_radix_int(start, start_pos, radix);
+        }
+
+        self.read_decimal_number(start, start_pos)
+    }
+
+    fn read_radix_int(&mut self, start: usize, start_pos: Position, radix: Radix) -> Literal<'src> {
+        let digits_start = self.position;
+        let mut any_digit = false;
+        let mut out_of_range = false;
         loop {
             match self.ch {
-                b'0'..=b'9' => self.next_char(),
-                b'.' => if decimal { break } else {
-                    decimal = true;
+                b'_' => self.next_char(),
+                c if radix.is_digit(c) => { any_digit = true; self.next_char(); }
+                c if c.is_ascii_alphanumeric() => { out_of_range = true; self.next_char(); }
+                _ => break,
+            }
+        }
+        let end = self.position;
+        let digits: String = self.input[digits_start..end].chars().filter(|&c| c != '_').collect();
+
+        if !any_digit {
+            self.error(Span { start, end, start_pos }, format!("no digits after {radix:?} prefix"));
+            return Literal::Int { value: 0, radix, invalid: true };
+        }
+        if out_of_range {
+            self.error(Span { start, end, start_pos }, format!("digit out of range for a {radix:?} literal"));
+            return Literal::Int { value: 0, radix, invalid: true };
+        }
+        match u64::from_str_radix(&digits, radix.base()) {
+            Ok(value) => Literal::Int { value, radix, invalid: false },
+            Err(_) => {
+                self.error(Span { start, end, start_pos }, "integer literal out of range");
+                Literal::Int { value: 0, radix, invalid: true }
+            }
+        }
+    }
+
+    fn read_decimal_number(&mut self, start: usize, start_pos: Position) -> Literal<'src> {
+        let mut is_float = false;
+        loop {
+            match self.ch {
+                b'0'..=b'9' | b'_' => self.next_char(),
+                b'.' if !is_float && matches!(self.peek_byte(), Some(b'0'..=b'9')) => {
+                    is_float = true;
                     self.next_char();
-                },
+                }
+                b'e' | b'E' => {
+                    is_float = true;
+                    self.next_char();
+                    if matches!(self.ch, b'+' | b'-') { self.next_char(); }
+                }
                 _ => break,
             }
         }
-        String::from_utf8_lossy(&self.input[pos..self.position])
-            .to_string()
-            .parse::<f64>()
-            .unwrap()
+        let end = self.position;
+        let text: String = self.input[start..end].chars().filter(|&c| c != '_').collect();
+
+        if is_float {
+            match text.parse::<f64>() {
+                Ok(value) => Literal::Float { value, invalid: false },
+                Err(_) => {
+                    self.error(Span { start, end, start_pos }, format!("invalid float literal `{text}`"));
+                    Literal::Float { value: 0.0, invalid: true }
+                }
+            }
+        } else {
+            match text.parse::<u64>() {
+                Ok(value) => Literal::Int { value, radix: Radix::Dec, invalid: false },
+                Err(_) => {
+                    self.error(Span { start, end, start_pos }, format!("invalid integer literal `{text}`"));
+                    Literal::Int { value: 0, radix: Radix::Dec, invalid: true }
+                }
+            }
+        }
     }
 
-    fn read_string_literal(&mut self) -> String { 
-        // TODO: escape quote \"
+    fn read_string_literal(&mut self) -> Literal<'src> {
         let quote = self.ch; // store the current single/double quote
+        let start_pos = self.current_position();
         self.next_char(); // advance to first char of string
-        let pos = self.position;
+        let start = self.position;
+        // Only the first escape forces an owned buffer; a string with no
+        // escapes stays a borrowed slice of the source.
+        let mut owned: Option<String> = None;
+        let mut segment_start = start;
+        let mut invalid = false;
+
         loop {
-            if self.ch == quote { break }
-            else { self.next_char() }
+            match self.ch {
+                0 => {
+                    self.error(Span { start, end: self.position, start_pos }, "unterminated string literal");
+                    invalid = true;
+                    break;
+                }
+                b'\\' => {
+                    let buf = owned.get_or_insert_with(String::new);
+                    buf.push_str(&self.input[segment_start..self.position]);
+                    self.next_char(); // move onto the escaped char
+                    match self.ch {
+                        0 => {
+                            self.error(Span { start, end: self.position, start_pos }, "unterminated string literal");
+                            invalid = true;
+                            break;
+                        }
+                        b'n'  => { buf.push('\n'); self.next_char(); }
+                        b't'  => { buf.push('\t'); self.next_char(); }
+                        b'r'  => { buf.push('\r'); self.next_char(); }
+                        b'0'  => { buf.push('\0'); self.next_char(); }
+                        b'\\' => { buf.push('\\'); self.next_char(); }
+                        b'\'' => { buf.push('\''); self.next_char(); }
+                        b'"'  => { buf.push('"'); self.next_char(); }
+                        b'u'  => {
+                            let esc_start = self.position - 1;
+                            let esc_pos = self.current_position();
+                            self.next_char(); // consume 'u'
+                            if self.ch != b'{' {
+                                self.error(Span { start: esc_start, end: self.position, start_pos: esc_pos }, "invalid \\u escape: expected `{`");
+                                invalid = true;
+                            } else {
+                                self.next_char(); // consume '{'
+                                let hex_start = self.position;
+                                loop {
+                                    if self.ch == b'}' { break }
+                                    if self.ch == 0 || self.ch == quote {
+                                        self.error(Span { start: esc_start, end: self.position, start_pos: esc_pos }, "unterminated \\u{...} escape");
+                                        invalid = true;
+                                        break;
+                                    }
+                                    self.next_char();
+                                }
+                                if self.ch == b'}' {
+                                    let hex = &self.input[hex_start..self.position];
+                                    match u32::from_str_radix(hex, 16).ok().and_then(char::from_u32) {
+                                        Some(c) => buf.push(c),
+                                        None => {
+                                            self.error(Span { start: esc_start, end: self.position, start_pos: esc_pos }, format!("invalid \\u{{...}} escape `{hex}`"));
+                                            invalid = true;
+                                        }
+                                    }
+                                    self.next_char(); // consume closing '}'
+                                }
+                            }
+                        }
+                        other => {
+                            self.error(Span { start: self.position - 1, end: self.position + 1, start_pos: self.current_position() }, format!("invalid escape sequence `\\{}`", other as char));
+                            invalid = true;
+                            self.next_char();
+                        }
+                    }
+                    segment_start = self.position;
+                }
+                c if c == quote => break,
+                _ => self.next_char(),
+            }
         }
-        String::from_utf8_lossy(&self.input[pos..self.position]).to_string()
+
+        let value = match owned {
+            Some(mut buf) => {
+                buf.push_str(&self.input[segment_start..self.position]);
+                Cow::Owned(buf)
+            }
+            None => Cow::Borrowed(&self.input[start..self.position]),
+        };
+        Literal::Str { value, invalid }
     }
 
-    fn read_ident(&mut self) -> String {
+    fn read_ident(&mut self) -> &'src str {
         let pos = self.position;
         while self.ch.is_ascii_alphabetic() || self.ch == b'_' {
             self.next_char();
         }
-        String::from_utf8_lossy(&self.input[pos..self.position]).to_string()
+        &self.input[pos..self.position]
     }
-    
+
     fn peek_match(&self, input: &str) -> Result<bool> {
-        if input.chars().count() + self.read_position >= self.input.len() { bail!("EOF") };
+        if input.chars().count() + self.read_position >= self.bytes().len() { bail!("EOF") };
 
         let mut forward = 1;
         for ch in input.chars() {
-            if self.input[self.position + forward] != ch as u8 { return Ok(false) }
+            if self.bytes()[self.position + forward] != ch as u8 { return Ok(false) }
             else { forward += 1 }
         }
         Ok(true)
     }
 
     fn prev_match(&self, input: u8) -> bool {
-        if self.input[self.position - 1] == input { true } else { false }
+        self.position > 0 && self.bytes()[self.position - 1] == input
+    }
+}
+
+impl<'src> Iterator for Lexer<'src> {
+    type Item = (Span, Token<'src>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done { return None }
+        let tok = self.next_token();
+        if tok.1 == Token::EOF { self.done = true; }
+        Some(tok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex_one_string(input: &str) -> Literal<'_> {
+        match Lexer::new(input).next_token().1 {
+            Token::Lit(lit) => lit,
+            other => panic!("expected a string literal, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn newline_lexes_as_newline_not_unknown() {
+        let toks: Vec<Token> = Lexer::new("a\nb").map(|(_, tok)| tok).collect();
+        assert!(toks.contains(&Token::NewLine));
+        assert!(!toks.iter().any(|t| matches!(t, Token::Unknown(_))));
+    }
+
+    fn comment_doc(input: &str) -> Option<DocPlacement> {
+        match Lexer::new(input).next_token().1 {
+            Token::Comment { doc, .. } => doc,
+            other => panic!("expected a comment, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn triple_slash_is_outer_doc_but_four_slashes_is_not() {
+        assert_eq!(comment_doc("/// doc"), Some(DocPlacement::Outer));
+        assert_eq!(comment_doc("//// not doc"), None);
+    }
+
+    #[test]
+    fn double_bang_slash_is_inner_doc() {
+        assert_eq!(comment_doc("//! doc"), Some(DocPlacement::Inner));
+    }
+
+    #[test]
+    fn block_doc_excludes_repeated_star_and_empty_body() {
+        assert_eq!(comment_doc("/** doc */"), Some(DocPlacement::Outer));
+        assert_eq!(comment_doc("/*** not doc */"), None);
+        assert_eq!(comment_doc("/**/"), None);
+        assert_eq!(comment_doc("/*! inner doc */"), Some(DocPlacement::Inner));
+    }
+
+    #[test]
+    fn string_without_escapes_borrows_the_source() {
+        match lex_one_string(r#""hello""#) {
+            Literal::Str { value, invalid } => {
+                assert_eq!(value, "hello");
+                assert!(matches!(value, Cow::Borrowed(_)));
+                assert!(!invalid);
+            }
+            other => panic!("expected a string literal, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn simple_escapes_decode_to_owned() {
+        match lex_one_string(r#""a\nb\tc\\d""#) {
+            Literal::Str { value, invalid } => {
+                assert_eq!(value, "a\nb\tc\\d");
+                assert!(matches!(value, Cow::Owned(_)));
+                assert!(!invalid);
+            }
+            other => panic!("expected a string literal, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unicode_escape_decodes_code_point() {
+        match lex_one_string(r#""\u{1F600}""#) {
+            Literal::Str { value, invalid } => {
+                assert_eq!(value, "\u{1F600}");
+                assert!(!invalid);
+            }
+            other => panic!("expected a string literal, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unknown_escape_is_marked_invalid() {
+        match lex_one_string(r#""\q""#) {
+            Literal::Str { invalid, .. } => assert!(invalid),
+            other => panic!("expected a string literal, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unterminated_string_is_marked_invalid() {
+        match lex_one_string("\"abc") {
+            Literal::Str { invalid, .. } => assert!(invalid),
+            other => panic!("expected a string literal, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn bad_unicode_escape_only_poisons_its_own_string() {
+        let toks: Vec<Token> = Lexer::new(r#""\u{bad" true"#).map(|(_, tok)| tok).collect();
+        match &toks[0] {
+            Token::Lit(Literal::Str { invalid, .. }) => assert!(invalid),
+            other => panic!("expected an invalid string literal, got {other:?}"),
+        }
+        assert!(
+            toks.contains(&Token::True),
+            "the closing quote and trailing `true` should still be tokenized, got {toks:?}"
+        );
+    }
+}
+
+/// Incremental re-lexing over a [`ropey::Rope`] buffer, for editor/LSP use.
+/// `Lexer` itself borrows `&'src str` and re-tokenizes that slice in one
+/// pass; `IncrementalLexer` instead owns a materialized copy of the buffer
+/// and a cached token stream, and `relex` only re-scans the region around an
+/// edit before splicing the result back into that cache.
+#[cfg(feature = "ropey")]
+pub mod incremental {
+    use super::{Lexer, Literal, Position, Radix, Span, Token};
+    use ropey::Rope;
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum OwnedLiteral {
+        Str { value: String, invalid: bool },
+        Int { value: u64, radix: Radix, invalid: bool },
+        Float { value: f64, invalid: bool },
+    }
+
+    impl<'src> From<Literal<'src>> for OwnedLiteral {
+        fn from(lit: Literal<'src>) -> Self {
+            match lit {
+                Literal::Str { value, invalid } => OwnedLiteral::Str { value: value.into_owned(), invalid },
+                Literal::Int { value, radix, invalid } => OwnedLiteral::Int { value, radix, invalid },
+                Literal::Float { value, invalid } => OwnedLiteral::Float { value, invalid },
+            }
+        }
+    }
+
+    /// An owned mirror of [`Token`] that doesn't borrow from the source
+    /// buffer, so cached entries can outlive the `&str` slice the `Lexer`
+    /// that produced them was given.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum OwnedToken {
+        Ident(String),
+        Lit(OwnedLiteral),
+        Let, Mut, Def, Struct, Enum, Object,
+        If, Elif, Else, Match, True, False,
+        NewLine, Space, Tab,
+        LParen, RParen, LSquirly, RSquirly, LBrack, RBrack,
+        SingleQuote, DoubleQuote, LAngle, RAngle,
+        Comma, Dot, Pipe, Plus, Dash, Underscore, Equal, FSlash, BSlash,
+        Colon, SemiColon, Bang, At, Octothorpe, Dollar, Percent, Caret,
+        Ampersand, Asterisk, Question, Tilde, Grave,
+        Comment { shape: super::CommentShape, doc: Option<super::DocPlacement>, text: String },
+        Unknown(u8),
+        EOF,
+    }
+
+    impl<'src> From<Token<'src>> for OwnedToken {
+        fn from(tok: Token<'src>) -> Self {
+            match tok {
+                Token::Ident(s) => OwnedToken::Ident(s.to_string()),
+                Token::Lit(lit) => OwnedToken::Lit(lit.into()),
+                Token::Let => OwnedToken::Let,
+                Token::Mut => OwnedToken::Mut,
+                Token::Def => OwnedToken::Def,
+                Token::Struct => OwnedToken::Struct,
+                Token::Enum => OwnedToken::Enum,
+                Token::Object => OwnedToken::Object,
+                Token::If => OwnedToken::If,
+                Token::Elif => OwnedToken::Elif,
+                Token::Else => OwnedToken::Else,
+                Token::Match => OwnedToken::Match,
+                Token::True => OwnedToken::True,
+                Token::False => OwnedToken::False,
+                Token::NewLine => OwnedToken::NewLine,
+                Token::Space => OwnedToken::Space,
+                Token::Tab => OwnedToken::Tab,
+                Token::LParen => OwnedToken::LParen,
+                Token::RParen => OwnedToken::RParen,
+                Token::LSquirly => OwnedToken::LSquirly,
+                Token::RSquirly => OwnedToken::RSquirly,
+                Token::LBrack => OwnedToken::LBrack,
+                Token::RBrack => OwnedToken::RBrack,
+                Token::SingleQuote => OwnedToken::SingleQuote,
+                Token::DoubleQuote => OwnedToken::DoubleQuote,
+                Token::LAngle => OwnedToken::LAngle,
+                Token::RAngle => OwnedToken::RAngle,
+                Token::Comma => OwnedToken::Comma,
+                Token::Dot => OwnedToken::Dot,
+                Token::Pipe => OwnedToken::Pipe,
+                Token::Plus => OwnedToken::Plus,
+                Token::Dash => OwnedToken::Dash,
+                Token::Underscore => OwnedToken::Underscore,
+                Token::Equal => OwnedToken::Equal,
+                Token::FSlash => OwnedToken::FSlash,
+                Token::BSlash => OwnedToken::BSlash,
+                Token::Colon => OwnedToken::Colon,
+                Token::SemiColon => OwnedToken::SemiColon,
+                Token::Bang => OwnedToken::Bang,
+                Token::At => OwnedToken::At,
+                Token::Octothorpe => OwnedToken::Octothorpe,
+                Token::Dollar => OwnedToken::Dollar,
+                Token::Percent => OwnedToken::Percent,
+                Token::Caret => OwnedToken::Caret,
+                Token::Ampersand => OwnedToken::Ampersand,
+                Token::Asterisk => OwnedToken::Asterisk,
+                Token::Question => OwnedToken::Question,
+                Token::Tilde => OwnedToken::Tilde,
+                Token::Grave => OwnedToken::Grave,
+                Token::Comment { shape, doc, text } => OwnedToken::Comment { shape, doc, text: text.to_string() },
+                Token::Unknown(b) => OwnedToken::Unknown(b),
+                Token::EOF => OwnedToken::EOF,
+            }
+        }
+    }
+
+    pub struct IncrementalLexer {
+        text: String,
+        lines: Vec<usize>,
+        tokens: Vec<(Span, OwnedToken)>,
+    }
+
+    impl IncrementalLexer {
+        pub fn new(rope: &Rope) -> Self {
+            let text = rope.to_string();
+            let tokens = Lexer::new(&text).map(|(span, tok)| (span, OwnedToken::from(tok))).collect();
+            let lines = newline_offsets(&text);
+            Self { text, lines, tokens }
+        }
+
+        pub fn tokens(&self) -> &[(Span, OwnedToken)] {
+            &self.tokens
+        }
+
+        /// Find the line start at or before `pos`, so re-lexing resumes from
+        /// a position that can't be in the middle of an escape or comment.
+        fn anchor_for(&self, pos: usize) -> usize {
+            match self.lines.partition_point(|&nl| nl < pos) {
+                0 => 0,
+                n => self.lines[n - 1] + 1,
+            }
+        }
+
+        /// The `Position` of byte offset `pos` in the current (post-edit)
+        /// `self.lines`, using the same 1-indexed line / 0-indexed column
+        /// convention as `Lexer`.
+        fn position_for(&self, pos: usize) -> Position {
+            match self.lines.partition_point(|&nl| nl < pos) {
+                0 => Position { line: 1, column: pos },
+                n => Position { line: n + 1, column: pos - (self.lines[n - 1] + 1) },
+            }
+        }
+
+        /// Re-lex the region touched by `edit` (the byte range in the buffer
+        /// *before* the edit) with it replaced by `new_text`, splice the
+        /// result into the cached token stream, and return the spans (in the
+        /// post-edit buffer) that changed.
+        pub fn relex(&mut self, edit: Span, new_text: &str) -> Vec<Span> {
+            let delta = new_text.len() as isize - (edit.end - edit.start) as isize;
+            self.text.replace_range(edit.start..edit.end, new_text);
+            self.lines = newline_offsets(&self.text);
+
+            let anchor = self.anchor_for(edit.start);
+            let keep_before = self.tokens.partition_point(|(span, _)| span.start < anchor);
+            let old_tail = self.tokens.split_off(keep_before);
+            let shifted_edit_end = (edit.end as isize + delta) as usize;
+
+            let mut lexer = Lexer::new(&self.text[anchor..]);
+            let mut changed = Vec::new();
+
+            loop {
+                let (span, tok) = lexer.next_token();
+                let owned = OwnedToken::from(tok);
+                let abs_start = span.start + anchor;
+                let abs_span = Span { start: abs_start, end: span.end + anchor, start_pos: self.position_for(abs_start) };
+                let is_eof = owned == OwnedToken::EOF;
+
+                if abs_span.start >= shifted_edit_end {
+                    let resync = old_tail.iter().position(|(old_span, old_tok)| {
+                        (old_span.start as isize + delta) as usize == abs_span.start && *old_tok == owned
+                    });
+                    if let Some(resume_at) = resync {
+                        for (s, t) in &old_tail[resume_at..] {
+                            let start = (s.start as isize + delta) as usize;
+                            self.tokens.push((
+                                Span {
+                                    start,
+                                    end: (s.end as isize + delta) as usize,
+                                    start_pos: self.position_for(start),
+                                },
+                                t.clone(),
+                            ));
+                        }
+                        return changed;
+                    }
+                }
+
+                changed.push(abs_span);
+                self.tokens.push((abs_span, owned));
+                if is_eof { return changed }
+            }
+        }
+    }
+
+    fn newline_offsets(text: &str) -> Vec<usize> {
+        text.bytes().enumerate().filter(|&(_, b)| b == b'\n').map(|(i, _)| i).collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn relex_recomputes_line_numbers_for_spliced_tail() {
+            let mut lexer = IncrementalLexer::new(&Rope::from_str("aaa\nbbb\nccc\n"));
+            lexer.relex(Span { start: 0, end: 0, start_pos: Position { line: 1, column: 0 } }, "zzz\n");
+
+            let lines: Vec<usize> = lexer
+                .tokens()
+                .iter()
+                .filter_map(|(span, tok)| match tok {
+                    OwnedToken::Ident(name) if matches!(name.as_str(), "aaa" | "bbb" | "ccc") => {
+                        Some(span.start_pos.line)
+                    }
+                    _ => None,
+                })
+                .collect();
+
+            assert_eq!(lines, vec![2, 3, 4]);
+        }
+
+        #[test]
+        fn relex_recomputes_line_numbers_for_freshly_lexed_tokens() {
+            let mut lexer = IncrementalLexer::new(&Rope::from_str("aaa\nbbb\nccc\nddd\n"));
+            // "ccc" is on line 3, at byte offset 8.
+            lexer.relex(Span { start: 8, end: 11, start_pos: Position { line: 3, column: 0 } }, "zzz");
+
+            let zzz_pos = lexer
+                .tokens()
+                .iter()
+                .find_map(|(span, tok)| match tok {
+                    OwnedToken::Ident(name) if name == "zzz" => Some(span.start_pos),
+                    _ => None,
+                })
+                .expect("zzz token");
+
+            assert_eq!(zzz_pos, Position { line: 3, column: 0 });
+        }
     }
 }